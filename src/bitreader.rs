@@ -0,0 +1,187 @@
+use std::collections::VecDeque;
+use std::io;
+use std::io::Read;
+
+/// Signals that a read could not be completed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitReaderError {
+	/// The underlying stream has no more data available *right now*, as
+	/// opposed to having truly ended. Callers should `rollback` to their
+	/// last checkpoint and retry once more input has been supplied.
+	WouldBlock,
+	/// The underlying stream has ended, or failed with an error other than
+	/// `io::ErrorKind::WouldBlock`.
+	Eof,
+}
+
+/// A saved bit position, taken with `BitReader::mark`. Pass it to
+/// `rollback` to undo any bits read since, or to `commit` once they're
+/// known not to be needed again.
+#[derive(Debug, Clone, Copy)]
+pub struct Checkpoint {
+	byte_pos: usize,
+	bit_buf: u8,
+	bits_in_buf: u8,
+}
+
+/// Reads a stream bit by bit, least-significant bit first, as required by
+/// the DEFLATE bitstream format (RFC 1951 §3.1.1).
+///
+/// Bytes pulled from `inner` are buffered until `commit`, so a caller that
+/// `mark`s before a logical unit (a symbol, a length/distance pair, a
+/// header field, ...) and hits `BitReaderError::WouldBlock` partway
+/// through can `rollback` and later resume from the exact same bits,
+/// without having to re-read from `inner`.
+pub struct BitReader<R> {
+	inner: R,
+	buf: VecDeque<u8>,
+	byte_pos: usize,
+	bit_buf: u8,
+	bits_in_buf: u8,
+	committed: usize,
+}
+
+impl<R: Read> BitReader<R> {
+	pub fn new(inner: R) -> BitReader<R> {
+		BitReader{
+			inner: inner,
+			buf: VecDeque::new(),
+			byte_pos: 0,
+			bit_buf: 0,
+			bits_in_buf: 0,
+			committed: 0,
+		}
+	}
+
+	fn next_byte(&mut self) -> Result<u8, BitReaderError> {
+		if self.byte_pos == self.buf.len() {
+			let mut byte = [0u8; 1];
+			match self.inner.read(&mut byte) {
+				Ok(0) => return Err(BitReaderError::Eof),
+				Ok(_) => self.buf.push_back(byte[0]),
+				Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Err(BitReaderError::WouldBlock),
+				Err(_) => return Err(BitReaderError::Eof),
+			}
+		}
+
+		let byte = self.buf[self.byte_pos];
+		self.byte_pos += 1;
+
+		Ok(byte)
+	}
+
+	pub fn read_bit(&mut self) -> Result<bool, BitReaderError> {
+		if self.bits_in_buf == 0 {
+			self.bit_buf = self.next_byte()?;
+			self.bits_in_buf = 8;
+		}
+
+		let bit = self.bit_buf & 1 == 1;
+		self.bit_buf >>= 1;
+		self.bits_in_buf -= 1;
+
+		Ok(bit)
+	}
+
+	pub fn read_n_bits(&mut self, n: u8) -> Result<Vec<bool>, BitReaderError> {
+		(0..n).map(|_| self.read_bit()).collect()
+	}
+
+	/// Looks ahead `n` bits without consuming them, most-significant bit
+	/// first (the order Huffman codes are packed in, as opposed to the
+	/// least-significant-bit-first order of every other DEFLATE field).
+	/// Leaves the reader positioned exactly where it was.
+	pub fn peek_bits(&mut self, n: u8) -> Result<u16, BitReaderError> {
+		let checkpoint = self.mark();
+
+		let mut value = 0u16;
+		for _ in 0..n {
+			match self.read_bit() {
+				Ok(bit) => value = (value << 1) | (bit as u16),
+				Err(err) => {
+					self.rollback(checkpoint);
+					return Err(err);
+				},
+			}
+		}
+
+		self.rollback(checkpoint);
+
+		Ok(value)
+	}
+
+	/// Consumes `n` bits without decoding them, as done after a `peek_bits`
+	/// call has already determined their value.
+	pub fn skip_bits(&mut self, n: u8) -> Result<(), BitReaderError> {
+		for _ in 0..n {
+			self.read_bit()?;
+		}
+
+		Ok(())
+	}
+
+	/// Discards any partially-read byte, so the next read starts at a byte
+	/// boundary of the underlying stream.
+	pub fn align_to_byte(&mut self) {
+		self.bit_buf = 0;
+		self.bits_in_buf = 0;
+	}
+
+	/// Like `align_to_byte`, but returns `false` instead of `true` if the
+	/// discarded bits were not all zero, so a caller expecting zero padding
+	/// (e.g. after a final DEFLATE block) can tell real trailing data from
+	/// padding.
+	pub fn align_to_byte_checked(&mut self) -> bool {
+		let all_zero = self.bit_buf == 0;
+		self.align_to_byte();
+
+		all_zero
+	}
+
+	/// Reads `n` bytes directly from the underlying stream. Must only be
+	/// called right after `align_to_byte`.
+	pub fn read_bytes(&mut self, n: usize) -> Result<Vec<u8>, BitReaderError> {
+		(0..n).map(|_| self.next_byte()).collect()
+	}
+
+	/// Saves the current bit position.
+	pub fn mark(&mut self) -> Checkpoint {
+		Checkpoint{
+			byte_pos: self.byte_pos,
+			bit_buf: self.bit_buf,
+			bits_in_buf: self.bits_in_buf,
+		}
+	}
+
+	/// Restores a previously saved bit position, undoing any bits read
+	/// since.
+	pub fn rollback(&mut self, checkpoint: Checkpoint) {
+		self.byte_pos = checkpoint.byte_pos;
+		self.bit_buf = checkpoint.bit_buf;
+		self.bits_in_buf = checkpoint.bits_in_buf;
+	}
+
+	/// Drops buffered bytes that no checkpoint will ever roll back past.
+	pub fn commit(&mut self, checkpoint: Checkpoint) {
+		self.committed += checkpoint.byte_pos;
+		self.buf.drain(..checkpoint.byte_pos);
+		self.byte_pos -= checkpoint.byte_pos;
+	}
+
+	/// Total number of input bytes read and not subsequently rolled back,
+	/// i.e. how far into the stream decoding has truly progressed.
+	pub fn bytes_consumed(&self) -> usize {
+		self.committed + self.byte_pos
+	}
+
+	/// Consumes the reader, returning the underlying stream chained after
+	/// any bytes that were already buffered but not yet consumed, so the
+	/// result continues reading from the first unconsumed byte. Should
+	/// only be called once positioned on a byte boundary (e.g. right after
+	/// `align_to_byte`), otherwise up to 7 unconsumed bits are lost.
+	pub fn into_inner(mut self) -> io::Chain<io::Cursor<Vec<u8>>, R> {
+		self.buf.drain(..self.byte_pos);
+
+		io::Cursor::new(self.buf.into_iter().collect()).chain(self.inner)
+	}
+}
@@ -0,0 +1,77 @@
+use std::io;
+use std::io::Write;
+
+/// Writes a stream bit by bit, least-significant bit first, as required by
+/// the DEFLATE bitstream format (RFC 1951 §3.1.1). The mirror image of
+/// `BitReader`.
+pub struct BitWriter<W> {
+	inner: W,
+	bit_buf: u8,
+	bits_in_buf: u8,
+}
+
+impl<W: Write> BitWriter<W> {
+	pub fn new(inner: W) -> BitWriter<W> {
+		BitWriter{
+			inner: inner,
+			bit_buf: 0,
+			bits_in_buf: 0,
+		}
+	}
+
+	pub fn write_bit(&mut self, bit: bool) -> io::Result<()> {
+		if bit {
+			self.bit_buf |= 1 << self.bits_in_buf;
+		}
+		self.bits_in_buf += 1;
+
+		if self.bits_in_buf == 8 {
+			self.inner.write_all(&[self.bit_buf])?;
+			self.bit_buf = 0;
+			self.bits_in_buf = 0;
+		}
+
+		Ok(())
+	}
+
+	/// Writes the low `n` bits of `value`, least-significant bit first, as
+	/// used by every DEFLATE field other than Huffman codes themselves.
+	pub fn write_value(&mut self, value: u32, n: u8) -> io::Result<()> {
+		for i in 0..n {
+			self.write_bit((value >> i) & 1 == 1)?;
+		}
+
+		Ok(())
+	}
+
+	/// Writes a canonical Huffman code, most-significant bit first.
+	pub fn write_code(&mut self, code: u16, length: u8) -> io::Result<()> {
+		for i in (0..length).rev() {
+			self.write_bit((code >> i) & 1 == 1)?;
+		}
+
+		Ok(())
+	}
+
+	/// Pads the current byte with zero bits, so the next write starts at a
+	/// byte boundary of the underlying stream.
+	pub fn align_to_byte(&mut self) -> io::Result<()> {
+		if self.bits_in_buf > 0 {
+			self.inner.write_all(&[self.bit_buf])?;
+			self.bit_buf = 0;
+			self.bits_in_buf = 0;
+		}
+
+		Ok(())
+	}
+
+	/// Writes `bytes` directly to the underlying stream. Must only be
+	/// called right after `align_to_byte`.
+	pub fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+		self.inner.write_all(bytes)
+	}
+
+	pub fn into_inner(self) -> W {
+		self.inner
+	}
+}
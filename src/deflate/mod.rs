@@ -1,12 +1,17 @@
-use ::bitreader::BitReader;
+use ::bitreader::{ BitReader, BitReaderError };
+use ::bitwriter::BitWriter;
 use ::huffman;
-use ::huffman::tree::Tree;
+use ::huffman::tree::{ Tree, TreeError };
 
+use std::cmp;
+use std::collections::HashMap;
 use std::collections::VecDeque;
 use std::error::Error;
 use std::fmt;
 use std::fmt::{ Display, Formatter };
+use std::io;
 use std::io::Read;
+use std::io::Write;
 use std::result;
 
 /// Wraps an input stream and provides methods for decompressing.
@@ -20,13 +25,60 @@ use std::result;
 ///
 /// let mut f = try!(File::open("compressed.txt.gz"));
 /// let mut deflate = Decompressor::new(f);
-pub struct Decompressor {
+pub struct Decompressor<R> {
+	in_stream: BitReader<R>,
 	header: Header,
 	state: State,
-	huffman_codes: Option<HuffmanCodes>,
-	output_buf: VecDeque<u8>,
+	huffman_codes: Option<CodeTable>,
+	distance_codes: Option<CodeTable>,
+	window: VecDeque<u8>,
+	pending_output: VecDeque<u8>,
 }
 
+/// Order in which code-length code lengths are stored in a dynamic
+/// Huffman header (RFC 1951 §3.2.7).
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// Maximum code length for the code-length alphabet itself: each one is
+/// written into a fixed 3-bit field in the dynamic header (RFC 1951
+/// §3.2.7), so it cannot exceed 7.
+const MAX_CODE_LENGTH_CODE_LENGTH: u8 = 7;
+
+/// Size of the LZ77 sliding window: back-references may not reach further
+/// back than this.
+const WINDOW_SIZE: usize = 32768;
+
+/// Base length and number of extra bits for length symbols 257...285
+/// (RFC 1951 §3.2.5), indexed by `symbol - 257`.
+const LENGTH_TABLE: [(u16, u8); 29] = [
+	(3, 0), (4, 0), (5, 0), (6, 0), (7, 0), (8, 0), (9, 0), (10, 0),
+	(11, 1), (13, 1), (15, 1), (17, 1),
+	(19, 2), (23, 2), (27, 2), (31, 2),
+	(35, 3), (43, 3), (51, 3), (59, 3),
+	(67, 4), (83, 4), (99, 4), (115, 4),
+	(131, 5), (163, 5), (195, 5), (227, 5),
+	(258, 0),
+];
+
+/// Base distance and number of extra bits for distance symbols 0...29
+/// (RFC 1951 §3.2.5), indexed by the distance symbol.
+const DISTANCE_TABLE: [(u16, u8); 30] = [
+	(1, 0), (2, 0), (3, 0), (4, 0),
+	(5, 1), (7, 1),
+	(9, 2), (13, 2),
+	(17, 3), (25, 3),
+	(33, 4), (49, 4),
+	(65, 5), (97, 5),
+	(129, 6), (193, 6),
+	(257, 7), (385, 7),
+	(513, 8), (769, 8),
+	(1025, 9), (1537, 9),
+	(2049, 10), (3073, 10),
+	(4097, 11), (6145, 11),
+	(8193, 12), (12289, 12),
+	(16385, 13), (24577, 13),
+];
+
 type BFinal = bool;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -38,6 +90,33 @@ enum BType {
 
 type HuffmanCodes = huffman::tree::Tree;
 
+/// A set of canonical Huffman codes for one alphabet, kept in two forms:
+/// `table`, a flat lookup consulted first for a one-step decode, and
+/// `tree`, the bit-by-bit walk that `table` falls back to for any code
+/// it doesn't resolve.
+#[derive(Debug, Clone, PartialEq)]
+struct CodeTable {
+	tree: HuffmanCodes,
+	table: huffman::table::Table,
+}
+
+fn build_code_table(lengths: Vec<u8>) -> result::Result<CodeTable, DecompressorError> {
+	Ok(CodeTable{
+		table: huffman::table::build(&lengths),
+		tree: huffman::codes_from_lengths(lengths)?,
+	})
+}
+
+/// The fixed literal/length and distance code lengths defined by RFC 1951
+/// §3.2.6, shared by the decoder's `BType::CompressedWithFixedHuffmanCodes`
+/// handling and the encoder's non-dynamic block strategy.
+fn fixed_huffman_lengths() -> (Vec<u8>, Vec<u8>) {
+	let literal_lengths = [vec!(8; 144), vec!(9; 112), vec!(7; 24), vec!(8; 8)].concat();
+	let distance_lengths = vec!(5; 30);
+
+	(literal_lengths, distance_lengths)
+}
+
 type Symbol = u16;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -61,14 +140,20 @@ enum State {
 	BFinal(BFinal),
 	BType(BType),
 	HandlingHuffmanCodes(BType),
-	HuffmanCodes(HuffmanCodes),
-	Symbol(Symbol)
+	HuffmanCodes(CodeTable, CodeTable),
+	Symbol(Symbol),
+	Done,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum DecompressorError {
 	UnexpectedEOF,
-	BlockTypeReserved
+	BlockTypeReserved,
+	LenNlenMismatch,
+	TrailingGarbage,
+	WouldBlock,
+	InvalidHuffmanCode,
+	InvalidDistance,
 }
 
 impl Display for DecompressorError {
@@ -82,130 +167,1132 @@ impl Error for DecompressorError {
 		match self {
 			&DecompressorError::UnexpectedEOF => "Encountered unexpected EOF",
 			&DecompressorError::BlockTypeReserved => "Reserved block type in deflate header",
+			&DecompressorError::LenNlenMismatch => "LEN and NLEN do not match in stored block header",
+			&DecompressorError::TrailingGarbage => "Non-zero padding bits after the final deflate block",
+			&DecompressorError::WouldBlock => "Not enough input buffered to continue decoding",
+			&DecompressorError::InvalidHuffmanCode => "Huffman code table in deflate stream is incomplete or over-subscribed",
+			&DecompressorError::InvalidDistance => "Back-reference distance reaches further back than any byte decoded so far",
 		}
 	}
 }
 
-impl Decompressor {
-	pub fn new() -> Decompressor {
+impl From<DecompressorError> for io::Error {
+	fn from(err: DecompressorError) -> io::Error {
+		let kind = match err {
+			DecompressorError::UnexpectedEOF => io::ErrorKind::UnexpectedEof,
+			DecompressorError::BlockTypeReserved | DecompressorError::LenNlenMismatch | DecompressorError::TrailingGarbage | DecompressorError::InvalidHuffmanCode | DecompressorError::InvalidDistance => io::ErrorKind::InvalidData,
+			DecompressorError::WouldBlock => io::ErrorKind::WouldBlock,
+		};
+
+		io::Error::new(kind, err)
+	}
+}
+
+impl From<BitReaderError> for DecompressorError {
+	fn from(err: BitReaderError) -> DecompressorError {
+		match err {
+			BitReaderError::WouldBlock => DecompressorError::WouldBlock,
+			BitReaderError::Eof => DecompressorError::UnexpectedEOF,
+		}
+	}
+}
+
+impl From<TreeError> for DecompressorError {
+	fn from(err: TreeError) -> DecompressorError {
+		match err {
+			TreeError::ConflictingCodes => DecompressorError::InvalidHuffmanCode,
+		}
+	}
+}
+
+impl<R: Read> Decompressor<R> {
+	pub fn new(inner: R) -> Decompressor<R> {
 		Decompressor{
+			in_stream: BitReader::new(inner),
 			header: Header::new(),
 			state: State::HeaderBegin,
 			huffman_codes: None,
-			output_buf: VecDeque::with_capacity(32768),
+			distance_codes: None,
+			window: VecDeque::with_capacity(WINDOW_SIZE),
+			pending_output: VecDeque::new(),
 		}
 	}
 
-	fn parse_bfinal<R: Read>(ref mut in_stream: &mut BitReader<R>) -> result::Result<State, DecompressorError> {
-		match in_stream.read_bit() {
-			Ok(bfinal) => Ok(State::BFinal(bfinal)),
-			Err(_) => Err(DecompressorError::UnexpectedEOF),
-		}
+	fn parse_bfinal(in_stream: &mut BitReader<R>) -> result::Result<State, DecompressorError> {
+		Ok(State::BFinal(in_stream.read_bit()?))
 	}
 
-	fn parse_btype<R: Read>(ref mut in_stream: &mut BitReader<R>) -> result::Result<State, DecompressorError> {
-		match in_stream.read_n_bits(2) {
-			Ok(btype) => match (btype[1], btype[0]) {
-				(false, false) => Ok(State::BType(BType::NoCompression)),
-				(false, true) => Ok(State::BType(BType::CompressedWithFixedHuffmanCodes)),
-				(true, false) => Ok(State::BType(BType::CompressedWithDynamicHuffmanCodes)),
-				(true, true) => Err(DecompressorError::BlockTypeReserved),
-			},
-			Err(_) => Err(DecompressorError::UnexpectedEOF),
+	fn parse_btype(in_stream: &mut BitReader<R>) -> result::Result<State, DecompressorError> {
+		let btype = in_stream.read_n_bits(2)?;
+
+		match (btype[1], btype[0]) {
+			(false, false) => Ok(State::BType(BType::NoCompression)),
+			(false, true) => Ok(State::BType(BType::CompressedWithFixedHuffmanCodes)),
+			(true, false) => Ok(State::BType(BType::CompressedWithDynamicHuffmanCodes)),
+			(true, true) => Err(DecompressorError::BlockTypeReserved),
 		}
 	}
 
 	fn create_fixed_huffman_codes() -> result::Result<State, DecompressorError> {
-		let lengths = [vec!(8; 144), vec!(9; 112), vec!(7; 24), vec!(8; 8)].concat();
+		let (literal_lengths, distance_lengths) = fixed_huffman_lengths();
 
-		Ok(State::HuffmanCodes(huffman::codes_from_lengths(lengths)))
+		Ok(State::HuffmanCodes(
+			build_code_table(literal_lengths)?,
+			build_code_table(distance_lengths)?,
+		))
 	}
 
-	fn parse_next_symbol<R: Read>(ref mut in_stream: &mut BitReader<R>, huffman_codes: &HuffmanCodes) -> result::Result<State, DecompressorError> {
+	fn read_value(in_stream: &mut BitReader<R>, n: u8) -> result::Result<u32, DecompressorError> {
+		let bits = in_stream.read_n_bits(n)?;
+
+		Ok(bits.iter().enumerate().fold(0u32, |acc, (i, &bit)| if bit { acc | (1 << i) } else { acc }))
+	}
+
+	// Reference bit-by-bit decode: walks `huffman_codes` one bit at a time.
+	// Used directly for the short-lived code-length alphabet, and as the
+	// fallback for any code `decode_symbol`'s fast table doesn't resolve.
+	//
+	// A well-formed canonical Huffman code is "complete": every bit path
+	// ends at a `Leaf`. Corrupted or maliciously crafted input can describe
+	// an incomplete code instead, one with unassigned (`Empty`) branches;
+	// walking into one is reported as `InvalidHuffmanCode` rather than
+	// panicking.
+	fn decode_symbol_slow(in_stream: &mut BitReader<R>, huffman_codes: &HuffmanCodes) -> result::Result<Symbol, DecompressorError> {
 		let mut tree = huffman_codes.clone();
 
 		loop {
-			match in_stream.read_bit() {
-				Ok(bit) =>
-					match tree.lookup(bit) {
-						Some(Tree::Leaf(symbol)) => return Ok(State::Symbol(symbol)),
-						Some(inner) => tree = inner,
-						None => unreachable!(),
-					},
-				Err(_) => return Err(DecompressorError::UnexpectedEOF),
+			match tree.lookup(in_stream.read_bit()?) {
+				Some(Tree::Leaf(symbol)) => return Ok(symbol),
+				Some(inner @ Tree::Node(..)) => tree = inner,
+				Some(Tree::Empty) | None => return Err(DecompressorError::InvalidHuffmanCode),
 			}
 		}
 	}
 
-	pub fn decompress<R: Read>(&mut self, ref mut in_stream: &mut BitReader<R>) -> VecDeque<u8> {
-		let mut buf = VecDeque::new();
+	// Decodes one symbol from `code_table`, peeking the next few bits and
+	// resolving them with a single table lookup. Falls back to the tree
+	// walk when the table resolves no code for the peeked bits, or when too
+	// little input is currently buffered to peek a full table index.
+	fn decode_symbol(in_stream: &mut BitReader<R>, code_table: &CodeTable) -> result::Result<Symbol, DecompressorError> {
+		if let Ok(peek) = in_stream.peek_bits(huffman::table::MAX_BITS) {
+			if let Some((symbol, length)) = code_table.table.lookup(peek) {
+				in_stream.skip_bits(length)?;
+				return Ok(symbol);
+			}
+		}
 
-		loop {
-			match self.state.clone() {
-				State::HeaderBegin => {
-					self.state = match Self::parse_bfinal(*in_stream) {
-						Ok(state) => state,
-						Err(e) => panic!(e),
+		Self::decode_symbol_slow(in_stream, &code_table.tree)
+	}
+
+	fn parse_next_symbol(in_stream: &mut BitReader<R>, code_table: &CodeTable) -> result::Result<State, DecompressorError> {
+		Self::decode_symbol(in_stream, code_table).map(State::Symbol)
+	}
+
+	// Decodes exactly `count` code lengths using the code-length alphabet
+	// tree, expanding the repeat symbols 16 (copy previous), 17 (short zero
+	// run) and 18 (long zero run) as they're encountered.
+	fn decode_code_lengths(in_stream: &mut BitReader<R>, code_length_tree: &HuffmanCodes, count: usize) -> result::Result<Vec<u8>, DecompressorError> {
+		let mut lengths = Vec::with_capacity(count);
+		let mut previous = 0u8;
+
+		while lengths.len() < count {
+			match Self::decode_symbol_slow(in_stream, code_length_tree)? {
+				symbol @ 0...15 => {
+					previous = symbol as u8;
+					lengths.push(previous);
+				},
+				16 => {
+					let repeat = 3 + Self::read_value(in_stream, 2)?;
+					for _ in 0..repeat {
+						lengths.push(previous);
 					}
 				},
-				State::BFinal(bfinal) => {
-					self.header.bfinal = Some(bfinal);
-					self.state = match Self::parse_btype(*in_stream) {
-						Ok(state) => state,
-						Err(e) => panic!(e),
+				17 => {
+					let repeat = 3 + Self::read_value(in_stream, 3)?;
+					previous = 0;
+					for _ in 0..repeat {
+						lengths.push(0);
 					}
 				},
-				State::BType(btype) => {
-					self.header.btype = Some(btype.clone());
-					self.state = State::HandlingHuffmanCodes(btype);
+				18 => {
+					let repeat = 11 + Self::read_value(in_stream, 7)?;
+					previous = 0;
+					for _ in 0..repeat {
+						lengths.push(0);
+					}
 				},
-				State::HandlingHuffmanCodes(BType::NoCompression) => {
-					unimplemented!();
+				_ => unreachable!(),
+			}
+		}
+
+		lengths.truncate(count);
+
+		Ok(lengths)
+	}
+
+	fn read_length(in_stream: &mut BitReader<R>, length_code: Symbol) -> result::Result<usize, DecompressorError> {
+		let (base, extra_bits) = LENGTH_TABLE[(length_code - 257) as usize];
+		let extra = if extra_bits > 0 { Self::read_value(in_stream, extra_bits)? } else { 0 };
+
+		Ok(base as usize + extra as usize)
+	}
+
+	fn read_distance(in_stream: &mut BitReader<R>, distance_codes: &CodeTable) -> result::Result<usize, DecompressorError> {
+		let distance_code = Self::decode_symbol(in_stream, distance_codes)?;
+		if distance_code as usize >= DISTANCE_TABLE.len() {
+			// Symbols 30 and 31 are reserved by RFC 1951 (no distance
+			// meaning), but HDIST can make a dynamic header assign either
+			// one a real code.
+			return Err(DecompressorError::InvalidHuffmanCode);
+		}
+
+		let (base, extra_bits) = DISTANCE_TABLE[distance_code as usize];
+		let extra = if extra_bits > 0 { Self::read_value(in_stream, extra_bits)? } else { 0 };
+
+		Ok(base as usize + extra as usize)
+	}
+
+	// Appends a decoded byte to the sliding window and to the queue of
+	// output bytes pending delivery through `Read::read`.
+	fn emit(&mut self, byte: u8) {
+		self.pending_output.push_back(byte);
+
+		self.window.push_back(byte);
+		if self.window.len() > WINDOW_SIZE {
+			self.window.pop_front();
+		}
+	}
+
+	// State to resume in once the current block has been fully consumed.
+	// For the final block, also stops the bit reader at the following byte
+	// boundary, so no bits are pulled past the deflate stream's end and a
+	// caller can resume parsing an outer container format right after it.
+	fn end_of_block_state(&mut self) -> result::Result<State, DecompressorError> {
+		if self.header.bfinal == Some(true) {
+			if !self.in_stream.align_to_byte_checked() {
+				return Err(DecompressorError::TrailingGarbage);
+			}
+
+			Ok(State::Done)
+		} else {
+			Ok(State::HeaderBegin)
+		}
+	}
+
+	fn read_stored_block(in_stream: &mut BitReader<R>) -> result::Result<Vec<u8>, DecompressorError> {
+		in_stream.align_to_byte();
+
+		let header = in_stream.read_bytes(4)?;
+
+		let len = header[0] as u16 | (header[1] as u16) << 8;
+		let nlen = header[2] as u16 | (header[3] as u16) << 8;
+
+		if len != !nlen {
+			return Err(DecompressorError::LenNlenMismatch);
+		}
+
+		Ok(in_stream.read_bytes(len as usize)?)
+	}
+
+	fn create_dynamic_huffman_codes(in_stream: &mut BitReader<R>) -> result::Result<State, DecompressorError> {
+		let hlit = Self::read_value(in_stream, 5)? as usize + 257;
+		let hdist = Self::read_value(in_stream, 5)? as usize + 1;
+		let hclen = Self::read_value(in_stream, 4)? as usize + 4;
+
+		let mut code_length_lengths = [0u8; 19];
+		for i in 0..hclen {
+			code_length_lengths[CODE_LENGTH_ORDER[i]] = Self::read_value(in_stream, 3)? as u8;
+		}
+
+		let code_length_tree = huffman::codes_from_lengths(code_length_lengths.to_vec())?;
+
+		let lengths = Self::decode_code_lengths(in_stream, &code_length_tree, hlit + hdist)?;
+		let (literal_lengths, distance_lengths) = lengths.split_at(hlit);
+
+		// HLIT/HDIST can make symbols 286, 287 (literal/length) and 30, 31
+		// (distance) decodable, but RFC 1951 reserves all four: a
+		// conforming stream never assigns them a code. Reject any header
+		// that does, rather than letting a decoded reserved symbol reach
+		// `step`'s or `read_distance`'s symbol handling unvalidated.
+		if literal_lengths.iter().skip(286).any(|&length| length != 0) {
+			return Err(DecompressorError::InvalidHuffmanCode);
+		}
+		if distance_lengths.iter().skip(30).any(|&length| length != 0) {
+			return Err(DecompressorError::InvalidHuffmanCode);
+		}
+
+		Ok(State::HuffmanCodes(
+			build_code_table(literal_lengths.to_vec())?,
+			build_code_table(distance_lengths.to_vec())?,
+		))
+	}
+
+	// Performs a single state transition. Returns `Ok(true)` once it has
+	// produced output or reached `State::Done`, `Ok(false)` to keep
+	// looping. On `Err(DecompressorError::WouldBlock)`, `self.state` is
+	// left untouched so the same transition can be retried later.
+	fn step(&mut self) -> result::Result<bool, DecompressorError> {
+		match self.state.clone() {
+			State::HeaderBegin => {
+				self.state = Self::parse_bfinal(&mut self.in_stream)?;
+				Ok(false)
+			},
+			State::BFinal(bfinal) => {
+				self.header.bfinal = Some(bfinal);
+				self.state = Self::parse_btype(&mut self.in_stream)?;
+				Ok(false)
+			},
+			State::BType(btype) => {
+				self.header.btype = Some(btype.clone());
+				self.state = State::HandlingHuffmanCodes(btype);
+				Ok(false)
+			},
+			State::HandlingHuffmanCodes(BType::NoCompression) => {
+				let bytes = Self::read_stored_block(&mut self.in_stream)?;
+
+				for byte in bytes {
+					self.emit(byte);
+				}
+
+				self.state = self.end_of_block_state()?;
+
+				Ok(true)
+			},
+			State::HandlingHuffmanCodes(BType::CompressedWithFixedHuffmanCodes) => {
+				self.state = Self::create_fixed_huffman_codes()?;
+				Ok(false)
+			},
+			State::HandlingHuffmanCodes(BType::CompressedWithDynamicHuffmanCodes) => {
+				self.state = Self::create_dynamic_huffman_codes(&mut self.in_stream)?;
+				Ok(false)
+			},
+			State::HuffmanCodes(huffman_codes, distance_codes) => {
+				self.huffman_codes = Some(huffman_codes);
+				self.distance_codes = Some(distance_codes);
+				self.state = Self::parse_next_symbol(&mut self.in_stream, self.huffman_codes.as_ref().unwrap())?;
+				Ok(false)
+			},
+			State::Symbol(byte @ 0...255) => {
+				// literal byte
+				let byte = byte as u8;
+
+				let next = Self::parse_next_symbol(&mut self.in_stream, self.huffman_codes.as_ref().unwrap())?;
+
+				self.emit(byte);
+				self.state = next;
+
+				Ok(true)
+			},
+			State::Symbol(256) => {
+				// end of block
+				self.state = self.end_of_block_state()?;
+				Ok(false)
+			},
+			State::Symbol(length_code @ 257...285) => {
+				// length/distance back-reference
+				let length = Self::read_length(&mut self.in_stream, length_code)?;
+				let distance = Self::read_distance(&mut self.in_stream, self.distance_codes.as_ref().unwrap())?;
+
+				if distance > self.window.len() {
+					return Err(DecompressorError::InvalidDistance);
+				}
+
+				let next = Self::parse_next_symbol(&mut self.in_stream, self.huffman_codes.as_ref().unwrap())?;
+
+				for _ in 0..length {
+					let byte = self.window[self.window.len() - distance];
+					self.emit(byte);
+				}
+
+				self.state = next;
+
+				Ok(true)
+			},
+			State::Symbol(_) => {
+				// Symbols 286 and 287 are reserved by RFC 1951 (no
+				// literal/length meaning); `create_dynamic_huffman_codes`
+				// rejects any header that assigns them a code, so reaching
+				// this arm means one slipped through from elsewhere (e.g.
+				// the fixed code, which defines codes for them too).
+				Err(DecompressorError::InvalidHuffmanCode)
+			},
+			State::Done => Ok(true),
+		}
+	}
+
+	// Drives `step` until it produces output or reaches `State::Done`,
+	// rolling back the bit reader to retry a transition that ran out of
+	// currently available input rather than losing its place.
+	fn advance(&mut self) -> result::Result<(), DecompressorError> {
+		loop {
+			let checkpoint = self.in_stream.mark();
+
+			match self.step() {
+				Ok(true) => {
+					self.in_stream.commit(checkpoint);
+					return Ok(());
 				},
-				State::HandlingHuffmanCodes(BType::CompressedWithFixedHuffmanCodes) => {
-					self.state = match Self::create_fixed_huffman_codes() {
-						Ok(state) => state,
-						Err(e) => panic!(e),
-					}
+				Ok(false) => {
+					self.in_stream.commit(checkpoint);
 				},
-				State::HandlingHuffmanCodes(BType::CompressedWithDynamicHuffmanCodes) => {
-					unimplemented!();
+				Err(DecompressorError::WouldBlock) => {
+					self.in_stream.rollback(checkpoint);
+					return Err(DecompressorError::WouldBlock);
 				},
-				State::HuffmanCodes(huffman_codes) => {
-					self.huffman_codes = Some(huffman_codes);
-					self.state = match Self::parse_next_symbol(*in_stream, self.huffman_codes.as_ref().unwrap()) {
-						Ok(state) => state,
-						Err(e) => panic!(e),
-					};
+				Err(e) => return Err(e),
+			}
+		}
+	}
+
+	/// Number of input bytes consumed so far. Stable once decoding has
+	/// reached `State::Done`: the final block leaves the reader aligned to
+	/// the following byte boundary, so this is exactly the size of the
+	/// deflate stream, with no trailing bits pulled from a following
+	/// container format.
+	pub fn bytes_consumed(&self) -> usize {
+		self.in_stream.bytes_consumed()
+	}
+
+	/// Recovers the underlying reader, positioned at the first byte not
+	/// consumed while decoding. Intended to be called once decoding has
+	/// reached `State::Done`, so a caller can go on to parse an outer
+	/// container format (e.g. a gzip trailer) from the same stream.
+	pub fn into_inner(self) -> io::Chain<io::Cursor<Vec<u8>>, R> {
+		self.in_stream.into_inner()
+	}
+}
+
+impl<R: Read> Read for Decompressor<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		while self.pending_output.is_empty() && self.state != State::Done {
+			self.advance()?;
+		}
+
+		let n = cmp::min(buf.len(), self.pending_output.len());
+		for slot in buf[..n].iter_mut() {
+			*slot = self.pending_output.pop_front().unwrap();
+		}
+
+		Ok(n)
+	}
+}
+
+/// Shortest match length an LZ77 back-reference can encode (RFC 1951
+/// §3.2.5, length symbol 257).
+const MIN_MATCH: usize = 3;
+
+/// Longest match length an LZ77 back-reference can encode (length symbol
+/// 285).
+const MAX_MATCH: usize = 258;
+
+/// How many candidate positions to try per match search. Bounds the
+/// encoder's running time on highly repetitive input at the cost of
+/// possibly missing a slightly longer match.
+const MAX_CHAIN: usize = 128;
+
+/// Controls how `Compressor` splits input into blocks and encodes each one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncodeOptions {
+	/// Target number of input bytes per block. A block may end up smaller
+	/// for the final block of the stream.
+	pub block_size: usize,
+	/// When `true`, build a dynamic Huffman code tailored to each block's
+	/// symbol frequencies (`BType::CompressedWithDynamicHuffmanCodes`).
+	/// When `false`, always use the fixed code from RFC 1951 §3.2.6.
+	/// Either way, a block is instead written stored (`BType::NoCompression`)
+	/// if that would be smaller.
+	pub dynamic_huffman: bool,
+}
+
+impl Default for EncodeOptions {
+	fn default() -> EncodeOptions {
+		EncodeOptions{
+			block_size: WINDOW_SIZE,
+			dynamic_huffman: true,
+		}
+	}
+}
+
+// One decision emitted by the LZ77 match finder.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+	Literal(u8),
+	Match{ length: u16, distance: u16 },
+}
+
+// Inverse of `read_length`: the length symbol, extra-bits value and extra
+// bit count for a match of `length`.
+fn length_to_code(length: u16) -> (Symbol, u16, u8) {
+	for (i, &(base, extra_bits)) in LENGTH_TABLE.iter().enumerate().rev() {
+		if length >= base {
+			return (257 + i as u16, length - base, extra_bits);
+		}
+	}
+
+	unreachable!()
+}
+
+// Inverse of `read_distance`: the distance symbol, extra-bits value and
+// extra bit count for a back-reference `distance` away.
+fn distance_to_code(distance: u16) -> (Symbol, u16, u8) {
+	for (i, &(base, extra_bits)) in DISTANCE_TABLE.iter().enumerate().rev() {
+		if distance >= base {
+			return (i as u16, distance - base, extra_bits);
+		}
+	}
+
+	unreachable!()
+}
+
+// How a dynamic header's code-length sequence was run-length encoded
+// (RFC 1951 §3.2.7); each variant is itself a symbol in the 19-entry
+// code-length alphabet.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CodeLengthToken {
+	Literal(u8),
+	RepeatPrevious(u8), // 3..=6 repeats, symbol 16
+	RepeatZeroShort(u8), // 3..=10 repeats, symbol 17
+	RepeatZeroLong(u8), // 11..=138 repeats, symbol 18
+}
+
+impl CodeLengthToken {
+	fn symbol(&self) -> usize {
+		match *self {
+			CodeLengthToken::Literal(value) => value as usize,
+			CodeLengthToken::RepeatPrevious(_) => 16,
+			CodeLengthToken::RepeatZeroShort(_) => 17,
+			CodeLengthToken::RepeatZeroLong(_) => 18,
+		}
+	}
+
+	fn extra(&self) -> (u32, u8) {
+		match *self {
+			CodeLengthToken::Literal(_) => (0, 0),
+			CodeLengthToken::RepeatPrevious(repeat) => (repeat as u32 - 3, 2),
+			CodeLengthToken::RepeatZeroShort(repeat) => (repeat as u32 - 3, 3),
+			CodeLengthToken::RepeatZeroLong(repeat) => (repeat as u32 - 11, 7),
+		}
+	}
+}
+
+// Greedily run-length encodes a dynamic header's code-length sequence
+// using repeat symbols 16 (copy previous), 17 (short zero run) and 18
+// (long zero run), the inverse of `decode_code_lengths`.
+fn run_length_encode_lengths(lengths: &[u8]) -> Vec<CodeLengthToken> {
+	let mut tokens = Vec::new();
+	let mut i = 0;
+
+	while i < lengths.len() {
+		let value = lengths[i];
+		let mut run = 1;
+		while i + run < lengths.len() && lengths[i + run] == value {
+			run += 1;
+		}
+
+		if value == 0 {
+			let mut remaining = run;
+			while remaining > 0 {
+				if remaining >= 11 {
+					let take = cmp::min(remaining, 138);
+					tokens.push(CodeLengthToken::RepeatZeroLong(take as u8));
+					remaining -= take;
+				} else if remaining >= 3 {
+					let take = cmp::min(remaining, 10);
+					tokens.push(CodeLengthToken::RepeatZeroShort(take as u8));
+					remaining -= take;
+				} else {
+					for _ in 0..remaining {
+						tokens.push(CodeLengthToken::Literal(0));
+					}
+					remaining = 0;
+				}
+			}
+		} else {
+			tokens.push(CodeLengthToken::Literal(value));
+
+			let mut remaining = run - 1;
+			while remaining >= 3 {
+				let take = cmp::min(remaining, 6);
+				tokens.push(CodeLengthToken::RepeatPrevious(take as u8));
+				remaining -= take;
+			}
+			for _ in 0..remaining {
+				tokens.push(CodeLengthToken::Literal(value));
+			}
+		}
+
+		i += run;
+	}
+
+	tokens
+}
+
+// The code lengths and canonical codes of one alphabet's Huffman code
+// (dynamic or fixed), used for both the size estimate and the actual bit
+// output.
+struct DynamicCode {
+	lengths: Vec<u8>,
+	codes: Vec<u16>,
+}
+
+impl DynamicCode {
+	fn build(frequencies: &[u64], limit: u8) -> DynamicCode {
+		let lengths = huffman::package_merge::lengths_from_frequencies(frequencies, limit);
+		let codes = huffman::canonical_codes(&lengths);
+
+		DynamicCode{ lengths: lengths, codes: codes }
+	}
+}
+
+// Everything needed to write a dynamic block's header: the literal/length
+// and distance code lengths actually used (trimmed to HLIT/HDIST), their
+// run-length-encoded form, and the code-length alphabet's own code used to
+// compress that form.
+struct DynamicHeader {
+	literal_lengths: Vec<u8>,
+	distance_lengths: Vec<u8>,
+	code_length_tokens: Vec<CodeLengthToken>,
+	code_length_code: DynamicCode,
+	hclen: usize,
+	bits: u64,
+}
+
+impl DynamicHeader {
+	fn build(literal_code: &DynamicCode, distance_code: &DynamicCode) -> DynamicHeader {
+		let hlit = cmp::max(257, used_length(&literal_code.lengths));
+		let hdist = cmp::max(1, used_length(&distance_code.lengths));
+
+		let literal_lengths = literal_code.lengths[..hlit].to_vec();
+		let distance_lengths = distance_code.lengths[..hdist].to_vec();
+
+		let mut combined = literal_lengths.clone();
+		combined.extend(distance_lengths.iter().cloned());
+
+		let code_length_tokens = run_length_encode_lengths(&combined);
+
+		let mut code_length_frequencies = vec![0u64; 19];
+		for token in &code_length_tokens {
+			code_length_frequencies[token.symbol()] += 1;
+		}
+
+		let code_length_code = DynamicCode::build(&code_length_frequencies, MAX_CODE_LENGTH_CODE_LENGTH);
+
+		let ordered_lengths: Vec<u8> = CODE_LENGTH_ORDER.iter().map(|&symbol| code_length_code.lengths[symbol]).collect();
+		let hclen = cmp::max(4, used_length(&ordered_lengths));
+
+		let mut bits = 5 + 5 + 4 + (hclen as u64) * 3;
+		for token in &code_length_tokens {
+			let (_, extra_bits) = token.extra();
+			bits += code_length_code.lengths[token.symbol()] as u64 + extra_bits as u64;
+		}
+
+		DynamicHeader{
+			literal_lengths: literal_lengths,
+			distance_lengths: distance_lengths,
+			code_length_tokens: code_length_tokens,
+			code_length_code: code_length_code,
+			hclen: hclen,
+			bits: bits,
+		}
+	}
+}
+
+// Highest index with a non-zero length, plus one; 0 if all are zero.
+fn used_length(lengths: &[u8]) -> usize {
+	lengths.iter().rposition(|&length| length != 0).map(|i| i + 1).unwrap_or(0)
+}
+
+/// Wraps an output stream and compresses bytes written to it into a
+/// DEFLATE stream.
+///
+/// # Examples
+///
+/// extern crate compression;
+///
+/// use compression::deflate::Compressor;
+/// use std::io::Write;
+///
+/// let mut compressor = Compressor::new(Vec::new());
+/// compressor.write_all(b"hello, world").unwrap();
+/// let compressed = compressor.finish().unwrap();
+pub struct Compressor<W> {
+	out_stream: BitWriter<W>,
+	options: EncodeOptions,
+	history: Vec<u8>,
+	// Absolute stream position of `history[0]`: bytes older than the 32 KiB
+	// window are dropped from `history` (see `prune_window`), so indexing
+	// it by an absolute position requires subtracting this first.
+	history_offset: usize,
+	hash_chains: HashMap<[u8; 3], Vec<usize>>,
+	block_start: usize,
+	finished: bool,
+}
+
+impl<W: Write> Compressor<W> {
+	pub fn new(inner: W) -> Compressor<W> {
+		Self::with_options(inner, EncodeOptions::default())
+	}
+
+	pub fn with_options(inner: W, options: EncodeOptions) -> Compressor<W> {
+		Compressor{
+			out_stream: BitWriter::new(inner),
+			options: options,
+			history: Vec::new(),
+			history_offset: 0,
+			hash_chains: HashMap::new(),
+			block_start: 0,
+			finished: false,
+		}
+	}
+
+	// Absolute stream position one past the last byte written so far.
+	fn stream_len(&self) -> usize {
+		self.history_offset + self.history.len()
+	}
+
+	fn insert_hash(hash_chains: &mut HashMap<[u8; 3], Vec<usize>>, history: &[u8], offset: usize, pos: usize) {
+		let key = [history[pos - offset], history[pos - offset + 1], history[pos - offset + 2]];
+		hash_chains.entry(key).or_insert_with(Vec::new).push(pos);
+	}
+
+	fn find_match(history: &[u8], offset: usize, pos: usize, end: usize, hash_chains: &HashMap<[u8; 3], Vec<usize>>) -> Option<(u16, u16)> {
+		let max_len = cmp::min(MAX_MATCH, end - pos);
+		if max_len < MIN_MATCH {
+			return None;
+		}
+
+		let key = [history[pos - offset], history[pos - offset + 1], history[pos - offset + 2]];
+		let candidates = match hash_chains.get(&key) {
+			Some(candidates) => candidates,
+			None => return None,
+		};
+
+		let min_pos = pos.saturating_sub(WINDOW_SIZE);
+
+		let mut best_len = 0;
+		let mut best_distance = 0;
+
+		for &candidate in candidates.iter().rev().take(MAX_CHAIN) {
+			if candidate < min_pos || candidate >= pos {
+				continue;
+			}
+
+			let mut len = 0;
+			while len < max_len && history[candidate - offset + len] == history[pos - offset + len] {
+				len += 1;
+			}
+
+			if len > best_len {
+				best_len = len;
+				best_distance = pos - candidate;
+			}
+
+			if best_len >= max_len {
+				break;
+			}
+		}
+
+		if best_len >= MIN_MATCH {
+			Some((best_len as u16, best_distance as u16))
+		} else {
+			None
+		}
+	}
+
+	// Drops history bytes and hash-chain entries more than one window
+	// behind `end`, the oldest position a later back-reference could still
+	// reach. Without this, `history` and `hash_chains` would retain every
+	// byte ever written for the lifetime of the `Compressor`, unbounded
+	// memory growth for long-lived/streaming use.
+	fn prune_window(&mut self, end: usize) {
+		let keep_from = end.saturating_sub(WINDOW_SIZE);
+		if keep_from <= self.history_offset {
+			return;
+		}
+
+		let drop = keep_from - self.history_offset;
+		self.history.drain(..drop);
+		self.history_offset = keep_from;
+
+		self.hash_chains.retain(|_, positions| {
+			positions.retain(|&pos| pos >= keep_from);
+			!positions.is_empty()
+		});
+	}
+
+	// Runs the LZ77 match finder over `self.history[start..end)`, emitting
+	// a literal or back-reference token per position consumed and
+	// recording every position visited that has at least `MIN_MATCH` bytes
+	// before `end` in `self.hash_chains` so later
+	// blocks (or later positions in this one) can match against it.
+	fn lz77_tokens(&mut self, start: usize, end: usize) -> Vec<Token> {
+		let mut tokens = Vec::new();
+		let mut pos = start;
+		let offset = self.history_offset;
+
+		while pos < end {
+			let found = Self::find_match(&self.history, offset, pos, end, &self.hash_chains);
+
+			match found {
+				Some((length, distance)) => {
+					let length = length as usize;
+
+					for i in 0..length {
+						if pos + i + MIN_MATCH <= end {
+							Self::insert_hash(&mut self.hash_chains, &self.history, offset, pos + i);
+						}
+					}
+
+					tokens.push(Token::Match{ length: length as u16, distance: distance });
+					pos += length;
 				},
-				State::Symbol(byte @ 0...255) => {
-					// literal byte
-					buf.push_front(byte as u8);
-					self.output_buf.push_front(byte as u8);
+				None => {
+					if pos + MIN_MATCH <= end {
+						Self::insert_hash(&mut self.hash_chains, &self.history, offset, pos);
+					}
+
+					tokens.push(Token::Literal(self.history[pos - offset]));
+					pos += 1;
+				},
+			}
+		}
+
+		tokens
+	}
 
-					self.state = match Self::parse_next_symbol(*in_stream, self.huffman_codes.as_ref().unwrap()) {
-						Ok(state) => state,
-						Err(e) => panic!(e),
-					};
+	// Frequency of each literal/length and distance symbol used by
+	// `tokens`, including the mandatory end-of-block symbol.
+	//
+	// Sized to 288, not the 286-symbol literal/length alphabet (0...285):
+	// `fixed_huffman_lengths` defines codes for symbols 286 and 287 too
+	// (RFC 1951 §3.2.6), and `estimate_bits` indexes this table by every
+	// symbol in whichever code is in play, fixed or dynamic.
+	fn token_frequencies(tokens: &[Token]) -> (Vec<u64>, Vec<u64>) {
+		let mut literal_frequencies = vec![0u64; 288];
+		let mut distance_frequencies = vec![0u64; 30];
 
-					println!("{:?}", byte);
+		for token in tokens {
+			match *token {
+				Token::Literal(byte) => literal_frequencies[byte as usize] += 1,
+				Token::Match{ length, distance } => {
+					let (length_symbol, _, _) = length_to_code(length);
+					literal_frequencies[length_symbol as usize] += 1;
 
-					return buf;
+					let (distance_symbol, _, _) = distance_to_code(distance);
+					distance_frequencies[distance_symbol as usize] += 1;
 				},
-				State::Symbol(256) => {
-					// end of block
-					println!("end-of-block");
-					unimplemented!()
+			}
+		}
+
+		literal_frequencies[256] += 1;
+
+		(literal_frequencies, distance_frequencies)
+	}
+
+	fn estimate_bits(literal_code: &DynamicCode, distance_code: &DynamicCode, literal_frequencies: &[u64], distance_frequencies: &[u64], tokens: &[Token], header_bits: u64) -> u64 {
+		let mut bits = header_bits;
+
+		// `literal_code`/`distance_code` may be trimmed to HLIT/HDIST, shorter
+		// than the frequency tables; every symbol past that point is unused
+		// (zero frequency), so it's enough to walk the shorter side.
+		for (symbol, &length) in literal_code.lengths.iter().enumerate() {
+			bits += literal_frequencies[symbol] * length as u64;
+		}
+		for (symbol, &length) in distance_code.lengths.iter().enumerate() {
+			bits += distance_frequencies[symbol] * length as u64;
+		}
+
+		for token in tokens {
+			if let Token::Match{ length, distance } = *token {
+				let (_, _, length_extra_bits) = length_to_code(length);
+				let (_, _, distance_extra_bits) = distance_to_code(distance);
+
+				bits += length_extra_bits as u64 + distance_extra_bits as u64;
+			}
+		}
+
+		bits
+	}
+
+	fn write_btype(out_stream: &mut BitWriter<W>, btype: &BType) -> io::Result<()> {
+		let (bit0, bit1) = match *btype {
+			BType::NoCompression => (false, false),
+			BType::CompressedWithFixedHuffmanCodes => (true, false),
+			BType::CompressedWithDynamicHuffmanCodes => (false, true),
+		};
+
+		out_stream.write_bit(bit0)?;
+		out_stream.write_bit(bit1)
+	}
+
+	fn write_tokens(out_stream: &mut BitWriter<W>, tokens: &[Token], literal_code: &DynamicCode, distance_code: &DynamicCode) -> io::Result<()> {
+		for token in tokens {
+			match *token {
+				Token::Literal(byte) => {
+					out_stream.write_code(literal_code.codes[byte as usize], literal_code.lengths[byte as usize])?;
 				},
-				State::Symbol(length_code @ 257...285) => {
-					// length code
-					println!("length code {:?}", length_code);
-					unimplemented!()
+				Token::Match{ length, distance } => {
+					let (length_symbol, length_extra, length_extra_bits) = length_to_code(length);
+					out_stream.write_code(literal_code.codes[length_symbol as usize], literal_code.lengths[length_symbol as usize])?;
+					if length_extra_bits > 0 {
+						out_stream.write_value(length_extra as u32, length_extra_bits)?;
+					}
+
+					let (distance_symbol, distance_extra, distance_extra_bits) = distance_to_code(distance);
+					out_stream.write_code(distance_code.codes[distance_symbol as usize], distance_code.lengths[distance_symbol as usize])?;
+					if distance_extra_bits > 0 {
+						out_stream.write_value(distance_extra as u32, distance_extra_bits)?;
+					}
 				},
-				State::Symbol(_) => {
-					unreachable!();
-				}
 			}
 		}
+
+		out_stream.write_code(literal_code.codes[256], literal_code.lengths[256])
+	}
+
+	fn write_dynamic_header(out_stream: &mut BitWriter<W>, header: &DynamicHeader) -> io::Result<()> {
+		out_stream.write_value(header.literal_lengths.len() as u32 - 257, 5)?;
+		out_stream.write_value(header.distance_lengths.len() as u32 - 1, 5)?;
+
+		out_stream.write_value(header.hclen as u32 - 4, 4)?;
+
+		for i in 0..header.hclen {
+			out_stream.write_value(header.code_length_code.lengths[CODE_LENGTH_ORDER[i]] as u32, 3)?;
+		}
+
+		for token in &header.code_length_tokens {
+			let symbol = token.symbol();
+			out_stream.write_code(header.code_length_code.codes[symbol], header.code_length_code.lengths[symbol])?;
+
+			let (extra, extra_bits) = token.extra();
+			if extra_bits > 0 {
+				out_stream.write_value(extra, extra_bits)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	// Encodes `self.history[self.block_start..end)` as one block, choosing
+	// between a fixed or dynamic Huffman code (per `self.options.
+	// dynamic_huffman`) and a stored block, whichever ends up smaller.
+	fn encode_block(&mut self, end: usize, is_final: bool) -> io::Result<()> {
+		let tokens = self.lz77_tokens(self.block_start, end);
+		let (literal_frequencies, distance_frequencies) = Self::token_frequencies(&tokens);
+
+		let stored_bits = 3 + 7 + 32 + (end - self.block_start) as u64 * 8;
+
+		let (btype, literal_code, distance_code, header, header_bits) = if self.options.dynamic_huffman {
+			let literal_code = DynamicCode::build(&literal_frequencies, huffman::table::MAX_BITS);
+			let distance_code = DynamicCode::build(&distance_frequencies, huffman::table::MAX_BITS);
+			let header = DynamicHeader::build(&literal_code, &distance_code);
+
+			let literal_code = DynamicCode{ codes: huffman::canonical_codes(&header.literal_lengths), lengths: header.literal_lengths.clone() };
+			let distance_code = DynamicCode{ codes: huffman::canonical_codes(&header.distance_lengths), lengths: header.distance_lengths.clone() };
+			let header_bits = 3 + header.bits;
+
+			(BType::CompressedWithDynamicHuffmanCodes, literal_code, distance_code, Some(header), header_bits)
+		} else {
+			let (literal_lengths, distance_lengths) = fixed_huffman_lengths();
+			let literal_code = DynamicCode{ codes: huffman::canonical_codes(&literal_lengths), lengths: literal_lengths };
+			let distance_code = DynamicCode{ codes: huffman::canonical_codes(&distance_lengths), lengths: distance_lengths };
+
+			(BType::CompressedWithFixedHuffmanCodes, literal_code, distance_code, None, 3)
+		};
+
+		let compressed_bits = Self::estimate_bits(&literal_code, &distance_code, &literal_frequencies, &distance_frequencies, &tokens, header_bits);
+
+		self.out_stream.write_bit(is_final)?;
+
+		if stored_bits < compressed_bits {
+			Self::write_btype(&mut self.out_stream, &BType::NoCompression)?;
+			self.out_stream.align_to_byte()?;
+
+			let data = &self.history[self.block_start - self.history_offset..end - self.history_offset];
+			let len = data.len() as u16;
+
+			self.out_stream.write_bytes(&[len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8])?;
+			self.out_stream.write_bytes(data)?;
+		} else {
+			Self::write_btype(&mut self.out_stream, &btype)?;
+
+			if let Some(header) = header {
+				Self::write_dynamic_header(&mut self.out_stream, &header)?;
+			}
+
+			Self::write_tokens(&mut self.out_stream, &tokens, &literal_code, &distance_code)?;
+		}
+
+		if is_final {
+			self.out_stream.align_to_byte()?;
+		}
+
+		self.block_start = end;
+		self.prune_window(end);
+
+		Ok(())
+	}
+
+	/// Finalizes the deflate stream, writing a final (possibly empty)
+	/// block, and returns the underlying writer.
+	pub fn finish(mut self) -> io::Result<W> {
+		if !self.finished {
+			let end = self.stream_len();
+			self.encode_block(end, true)?;
+			self.finished = true;
+		}
+
+		Ok(self.out_stream.into_inner())
+	}
+}
+
+impl<W: Write> Write for Compressor<W> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.history.extend_from_slice(buf);
+
+		while self.stream_len() - self.block_start >= self.options.block_size {
+			let end = self.block_start + self.options.block_size;
+			self.encode_block(end, false)?;
+		}
+
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn round_trip(data: &[u8], options: EncodeOptions) -> Vec<u8> {
+		let mut compressor = Compressor::with_options(Vec::new(), options);
+		compressor.write_all(data).unwrap();
+		let compressed = compressor.finish().unwrap();
+
+		let mut decompressor = Decompressor::new(&compressed[..]);
+		let mut decoded = Vec::new();
+		decompressor.read_to_end(&mut decoded).unwrap();
+
+		decoded
+	}
+
+	fn sample_text() -> Vec<u8> {
+		b"the quick brown fox jumps over the lazy dog, the quick brown fox jumps again".to_vec()
+	}
+
+	#[test]
+	fn round_trips_with_dynamic_huffman_codes() {
+		let data = sample_text();
+		let options = EncodeOptions{ dynamic_huffman: true, ..EncodeOptions::default() };
+
+		assert_eq!(round_trip(&data, options), data);
+	}
+
+	#[test]
+	fn round_trips_with_fixed_huffman_codes() {
+		let data = sample_text();
+		let options = EncodeOptions{ dynamic_huffman: false, ..EncodeOptions::default() };
+
+		assert_eq!(round_trip(&data, options), data);
+	}
+
+	#[test]
+	fn round_trips_empty_input() {
+		assert_eq!(round_trip(&[], EncodeOptions::default()), Vec::<u8>::new());
+	}
+
+	#[test]
+	fn falls_back_to_a_stored_block_for_incompressible_data() {
+		// High bytes of a multiplicative hash: close enough to uniformly
+		// distributed that a dynamic header's overhead costs more than it
+		// saves, so `encode_block` should pick `BType::NoCompression`.
+		let data: Vec<u8> = (0u32..200).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8).collect();
+
+		let mut compressor = Compressor::new(Vec::new());
+		compressor.write_all(&data).unwrap();
+		let compressed = compressor.finish().unwrap();
+
+		let mut in_stream = BitReader::new(&compressed[..]);
+		in_stream.read_bit().unwrap(); // BFINAL
+		let btype = Decompressor::<&[u8]>::parse_btype(&mut in_stream).unwrap();
+		assert_eq!(btype, State::BType(BType::NoCompression));
+
+		let mut decompressor = Decompressor::new(&compressed[..]);
+		let mut decoded = Vec::new();
+		decompressor.read_to_end(&mut decoded).unwrap();
+		assert_eq!(decoded, data);
+	}
+
+	#[test]
+	fn tree_insert_rejects_conflicting_codes() {
+		// Symbol 0 takes the 1-bit code "0"; symbol 1 then claims the 2-bit
+		// code "00", which would require walking straight through symbol
+		// 0's leaf rather than extending past it.
+		let tree = Tree::Empty.insert(0b0, 1, 0).unwrap();
+
+		assert_eq!(tree.insert(0b00, 2, 1), Err(TreeError::ConflictingCodes));
+	}
+
+	#[test]
+	fn decode_symbol_slow_rejects_an_incomplete_code() {
+		// Symbol 0 takes the 1-bit code "0"; no symbol is assigned to "1",
+		// so a decoder reading a "1" bit walks into the unassigned branch.
+		let tree = Tree::Node(Box::new(Tree::Leaf(0)), Box::new(Tree::Empty));
+		let mut in_stream = BitReader::new(&[0b0000_0001u8][..]);
+
+		let result = Decompressor::<&[u8]>::decode_symbol_slow(&mut in_stream, &tree);
+
+		assert_eq!(result, Err(DecompressorError::InvalidHuffmanCode));
+	}
+
+	#[test]
+	fn step_rejects_a_back_reference_before_any_literal_has_been_decoded() {
+		// Fixed distance code 0 ("00000") encodes distance 1, one byte back
+		// -- but nothing has been decoded into `window` yet.
+		let (_, distance_lengths) = fixed_huffman_lengths();
+		let distance_codes = build_code_table(distance_lengths).unwrap();
+
+		let mut decompressor = Decompressor::new(&[0u8; 3][..]);
+		decompressor.state = State::Symbol(257); // length code for length 3
+		decompressor.distance_codes = Some(distance_codes);
+
+		assert_eq!(decompressor.step(), Err(DecompressorError::InvalidDistance));
+	}
+
+	#[test]
+	fn read_distance_rejects_a_reserved_distance_symbol() {
+		// A distance code table where the only assigned code belongs to
+		// symbol 31, reserved by RFC 1951 and absent from DISTANCE_TABLE.
+		let mut lengths = vec![0u8; 32];
+		lengths[31] = 5;
+		let distance_codes = build_code_table(lengths).unwrap();
+		let mut in_stream = BitReader::new(&[0u8; 3][..]);
+
+		let result = Decompressor::<&[u8]>::read_distance(&mut in_stream, &distance_codes);
+
+		assert_eq!(result, Err(DecompressorError::InvalidHuffmanCode));
+	}
+
+	#[test]
+	fn step_rejects_a_reserved_literal_length_symbol() {
+		let mut decompressor = Decompressor::new(&[][..]);
+		decompressor.state = State::Symbol(286);
+
+		assert_eq!(decompressor.step(), Err(DecompressorError::InvalidHuffmanCode));
+	}
+
+	#[test]
+	fn decoding_malformed_input_never_panics() {
+		// No structure here lines up with a valid deflate stream; decoding
+		// it should fail gracefully, not panic, regardless of exactly which
+		// error it's reported as.
+		let garbage = [0xffu8; 64];
+
+		let mut decompressor = Decompressor::new(&garbage[..]);
+		let mut decoded = Vec::new();
+		let _ = decompressor.read_to_end(&mut decoded);
 	}
 }
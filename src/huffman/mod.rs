@@ -0,0 +1,60 @@
+pub mod package_merge;
+pub mod table;
+pub mod tree;
+
+use self::tree::{ Tree, TreeError };
+
+/// Assigns the canonical code value for each symbol from its code length,
+/// as described in RFC 1951 §3.2.2 (the same bl_count/next_code
+/// computation used by `codes_from_lengths` and `table::build`). A length
+/// of 0 means the symbol is unused; its code value is 0 and should be
+/// ignored.
+pub(crate) fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+	let max_bits = lengths.iter().cloned().max().unwrap_or(0) as usize;
+
+	let mut bl_count = vec![0u16; max_bits + 1];
+	for &length in lengths.iter() {
+		if length > 0 {
+			bl_count[length as usize] += 1;
+		}
+	}
+
+	let mut code = 0u16;
+	let mut next_code = vec![0u16; max_bits + 1];
+	for bits in 1..(max_bits + 1) {
+		code = (code + bl_count[bits - 1]) << 1;
+		next_code[bits] = code;
+	}
+
+	let mut codes = vec![0u16; lengths.len()];
+	for (symbol, &length) in lengths.iter().enumerate() {
+		if length == 0 {
+			continue;
+		}
+
+		let length = length as usize;
+		codes[symbol] = next_code[length];
+		next_code[length] += 1;
+	}
+
+	codes
+}
+
+/// Builds a canonical Huffman code tree from a list of per-symbol code
+/// lengths, as described in RFC 1951 §3.2.2. A length of 0 means the
+/// symbol is unused and is skipped. Fails if the lengths describe an
+/// over-subscribed code, e.g. supplied by untrusted/corrupted input.
+pub fn codes_from_lengths(lengths: Vec<u8>) -> Result<Tree, TreeError> {
+	let codes = canonical_codes(&lengths);
+
+	let mut tree = Tree::Empty;
+	for (symbol, &length) in lengths.iter().enumerate() {
+		if length == 0 {
+			continue;
+		}
+
+		tree = tree.insert(codes[symbol], length as usize, symbol as u16)?;
+	}
+
+	Ok(tree)
+}
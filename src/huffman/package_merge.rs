@@ -0,0 +1,61 @@
+/// Computes per-symbol code lengths, each at most `limit` bits, optimal
+/// for the given frequencies, using the package-merge (coin-collector's
+/// problem) algorithm. A frequency of 0 always yields a length of 0
+/// (unused), matching the convention used throughout this module.
+pub fn lengths_from_frequencies(frequencies: &[u64], limit: u8) -> Vec<u8> {
+	let mut lengths = vec![0u8; frequencies.len()];
+
+	let mut items: Vec<(u64, usize)> = frequencies.iter().cloned().enumerate()
+		.filter(|&(_, frequency)| frequency > 0)
+		.map(|(symbol, frequency)| (frequency, symbol))
+		.collect();
+
+	if items.len() == 1 {
+		lengths[items[0].1] = 1;
+		return lengths;
+	}
+
+	if items.len() < 2 {
+		return lengths;
+	}
+
+	items.sort_by_key(|&(frequency, _)| frequency);
+
+	// The base "coin list": one coin of weight `frequency` per symbol,
+	// present unchanged at every level.
+	let base: Vec<(u64, Vec<usize>)> = items.iter().map(|&(frequency, symbol)| (frequency, vec![symbol])).collect();
+
+	// `level` starts as the level-1 list (just the base coins) and is
+	// rebuilt by packaging pairs from the previous level together with a
+	// fresh copy of the base coins, for `limit` levels in total.
+	let mut level = base.clone();
+
+	for _ in 1..limit {
+		let mut packaged: Vec<(u64, Vec<usize>)> = Vec::with_capacity(level.len() / 2);
+
+		for pair in level.chunks(2) {
+			if pair.len() == 2 {
+				let mut symbols = pair[0].1.clone();
+				symbols.extend(pair[1].1.iter().cloned());
+				packaged.push((pair[0].0 + pair[1].0, symbols));
+			}
+		}
+
+		packaged.extend(base.iter().cloned());
+		packaged.sort_by_key(|&(frequency, _)| frequency);
+
+		level = packaged;
+	}
+
+	// The cheapest `2 * n - 2` items of the final level, taken together,
+	// contain each symbol exactly as many times as its optimal code length.
+	let take = 2 * items.len() - 2;
+
+	for &(_, ref symbols) in level.iter().take(take) {
+		for &symbol in symbols {
+			lengths[symbol] += 1;
+		}
+	}
+
+	lengths
+}
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+/// Maximum canonical Huffman code length used by DEFLATE (RFC 1951
+/// §3.2.7).
+pub const MAX_BITS: u8 = 15;
+
+/// Width of the root table: codes up to this length are resolved with a
+/// single lookup; longer codes spill into a secondary sub-table.
+const ROOT_BITS: u8 = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Entry {
+	Invalid,
+	Symbol{ symbol: u16, length: u8 },
+	SubTable{ index: usize, bits: u8 },
+}
+
+/// A flat lookup table built from a set of canonical Huffman code
+/// lengths, indexed by the next `MAX_BITS` bits peeked from the input
+/// (most-significant bit of the code first). Each resolved entry gives
+/// both the decoded symbol and its true code length, so the reader can
+/// consume exactly that many bits in one step instead of walking the
+/// code tree bit by bit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+	root: Vec<Entry>,
+	sub_tables: Vec<Vec<Entry>>,
+}
+
+// Fills every slot of `table` whose top `length` bits equal `code` with
+// `entry`, across all combinations of the remaining low bits.
+fn fill(table: &mut [Entry], index_bits: u8, code: u16, length: u8, entry: Entry) {
+	let fill_bits = index_bits - length;
+	let base = (code as usize) << fill_bits;
+
+	for i in 0..(1usize << fill_bits) {
+		table[base + i] = entry;
+	}
+}
+
+/// Builds a two-level lookup table from per-symbol code lengths, as
+/// produced by `huffman::codes_from_lengths`'s canonical code assignment.
+pub fn build(lengths: &[u8]) -> Table {
+	let codes = super::canonical_codes(lengths);
+
+	let mut root = vec![Entry::Invalid; 1 << ROOT_BITS];
+	let mut long_codes = Vec::new();
+
+	for (symbol, &length) in lengths.iter().enumerate() {
+		if length == 0 {
+			continue;
+		}
+
+		let code = codes[symbol];
+
+		if length <= ROOT_BITS {
+			fill(&mut root, ROOT_BITS, code, length, Entry::Symbol{ symbol: symbol as u16, length: length });
+		} else {
+			long_codes.push((code, length, symbol as u16));
+		}
+	}
+
+	let mut sub_tables = Vec::new();
+
+	if !long_codes.is_empty() {
+		let mut groups: HashMap<u16, Vec<(u16, u8, u16)>> = HashMap::new();
+
+		for (code, length, symbol) in long_codes {
+			let extra_bits = length - ROOT_BITS;
+			let prefix = code >> extra_bits;
+
+			groups.entry(prefix).or_insert_with(Vec::new).push((code & ((1 << extra_bits) - 1), extra_bits, symbol));
+		}
+
+		for (prefix, entries) in groups {
+			let max_extra_bits = entries.iter().map(|&(_, extra_bits, _)| extra_bits).max().unwrap();
+			let mut sub_table = vec![Entry::Invalid; 1 << max_extra_bits];
+
+			for (extra_code, extra_bits, symbol) in entries {
+				fill(&mut sub_table, max_extra_bits, extra_code, extra_bits, Entry::Symbol{ symbol: symbol, length: extra_bits });
+			}
+
+			let index = sub_tables.len();
+			sub_tables.push(sub_table);
+
+			fill(&mut root, ROOT_BITS, prefix, ROOT_BITS, Entry::SubTable{ index: index, bits: max_extra_bits });
+		}
+	}
+
+	Table{ root: root, sub_tables: sub_tables }
+}
+
+impl Table {
+	/// Resolves `peek`, the next `MAX_BITS` bits of input (MSB of the
+	/// code first), to a decoded symbol and its code length. Returns
+	/// `None` when no assigned code is a prefix of `peek`, as with an
+	/// incomplete code; the caller should fall back to the bit-by-bit tree
+	/// walk.
+	pub fn lookup(&self, peek: u16) -> Option<(u16, u8)> {
+		let root_index = (peek >> (MAX_BITS - ROOT_BITS)) as usize;
+
+		match self.root[root_index] {
+			Entry::Symbol{ symbol, length } => Some((symbol, length)),
+			Entry::SubTable{ index, bits } => {
+				let shift = MAX_BITS - ROOT_BITS - bits;
+				let sub_index = ((peek >> shift) & ((1 << bits) - 1)) as usize;
+
+				match self.sub_tables[index][sub_index] {
+					Entry::Symbol{ symbol, length } => Some((symbol, ROOT_BITS + length)),
+					_ => None,
+				}
+			},
+			Entry::Invalid => None,
+		}
+	}
+}
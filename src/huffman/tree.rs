@@ -0,0 +1,54 @@
+/// A canonical Huffman code tree, walked one bit at a time.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tree {
+	Empty,
+	Leaf(u16),
+	Node(Box<Tree>, Box<Tree>),
+}
+
+/// Signals that a set of code lengths does not describe a valid canonical
+/// Huffman code, e.g. supplied by untrusted/corrupted compressed input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TreeError {
+	/// Two codes collided: one code is equal to or a prefix of another, so
+	/// the path a code needs already holds another symbol or other codes.
+	ConflictingCodes,
+}
+
+impl Tree {
+	/// Follows `bit` from an inner node, returning the resulting subtree
+	/// (a `Leaf` if this completes a code, or another `Node` otherwise).
+	/// Returns `None` if called on a `Leaf` or `Empty` tree.
+	pub fn lookup(&self, bit: bool) -> Option<Tree> {
+		match *self {
+			Tree::Node(ref zero, ref one) => Some(if bit { (**one).clone() } else { (**zero).clone() }),
+			Tree::Leaf(_) | Tree::Empty => None,
+		}
+	}
+
+	/// Inserts `symbol` at the path given by the top `len` bits of `code`,
+	/// most-significant bit first, growing `Empty` nodes as needed. Fails
+	/// if `code` collides with a code already assigned to another symbol.
+	pub fn insert(self, code: u16, len: usize, symbol: u16) -> Result<Tree, TreeError> {
+		if len == 0 {
+			return match self {
+				Tree::Empty => Ok(Tree::Leaf(symbol)),
+				Tree::Leaf(_) | Tree::Node(..) => Err(TreeError::ConflictingCodes),
+			};
+		}
+
+		let (zero, one) = match self {
+			Tree::Node(zero, one) => (*zero, *one),
+			Tree::Empty => (Tree::Empty, Tree::Empty),
+			Tree::Leaf(_) => return Err(TreeError::ConflictingCodes),
+		};
+
+		let bit = (code >> (len - 1)) & 1 == 1;
+
+		if bit {
+			Ok(Tree::Node(Box::new(zero), Box::new(one.insert(code, len - 1, symbol)?)))
+		} else {
+			Ok(Tree::Node(Box::new(zero.insert(code, len - 1, symbol)?), Box::new(one)))
+		}
+	}
+}
@@ -0,0 +1,4 @@
+pub mod bitreader;
+pub mod bitwriter;
+pub mod huffman;
+pub mod deflate;
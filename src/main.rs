@@ -1,19 +1,17 @@
 extern crate compression;
 
 fn main() {
-	use std::io::{ Cursor, Read };
-	use compression::brotli::Decompressor;
-	use compression::bitreader::BitReader;
+	use std::io::{ Read, Write };
+	use compression::deflate::{ Compressor, Decompressor };
 
-	let brotli_stream = BitReader::new(Cursor::new(vec![
-		0x0b, 0x00, 0x80, 0x58, 0x03,
-	]));
+	let mut compressor = Compressor::new(Vec::new());
+	compressor.write_all(b"hello, world").unwrap();
+	let compressed = compressor.finish().unwrap();
 
-	let mut decompressed = &mut String::new();
-	let _ = Decompressor::new(brotli_stream).read_to_string(&mut decompressed);
+	let mut decompressed = String::new();
+	Decompressor::new(&compressed[..]).read_to_string(&mut decompressed).unwrap();
 
-	assert_eq!("", decompressed);
+	assert_eq!("hello, world", decompressed);
 
 	println!("{:?}", decompressed);
 }
-